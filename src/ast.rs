@@ -1,6 +1,8 @@
 use std::{
+    convert::TryInto,
     hash::Hash,
     ops::{BitAnd, BitOr, RangeInclusive},
+    sync::OnceLock,
 };
 
 use crate::{hashcons::VecHashCons, pp::PrettyPrinter};
@@ -590,3 +592,947 @@ impl BitOr for NextByte {
         }
     }
 }
+
+/// A partition of the byte alphabet into equivalence classes, such that any
+/// two bytes in the same class are accepted or rejected identically by every
+/// `Expr::Byte`/`Expr::ByteSet` reachable from the expression the classes
+/// were built from. Lets the derivative/DFA layer iterate over classes
+/// instead of every byte in `0..alphabet_size`.
+#[derive(Clone)]
+pub struct ByteClasses {
+    // class id for every byte 0..=255
+    classes: Vec<u8>,
+    num_classes: usize,
+}
+
+impl ByteClasses {
+    #[inline(always)]
+    pub fn get(&self, b: u8) -> u8 {
+        self.classes[b as usize]
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    /// One byte per class, in class-id order.
+    pub fn representative_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let mut seen = vec![false; self.num_classes];
+        (0u8..=255u8).filter(move |&b| {
+            let c = self.classes[b as usize] as usize;
+            if seen[c] {
+                false
+            } else {
+                seen[c] = true;
+                true
+            }
+        })
+    }
+
+    fn from_low_alphabet(classes_low: Vec<u8>, num_classes_low: usize, alphabet_size: usize) -> Self {
+        let mut classes = vec![0u8; 256];
+        classes[..alphabet_size].copy_from_slice(&classes_low);
+        let mut num_classes = num_classes_low;
+        if alphabet_size < 256 {
+            // bytes outside the declared alphabet are never distinguished by
+            // any Byte/ByteSet, but they must still get their own class
+            // rather than silently merging into some in-alphabet class.
+            let out_of_alphabet_class = num_classes_low as u8;
+            for b in classes.iter_mut().skip(alphabet_size) {
+                *b = out_of_alphabet_class;
+            }
+            num_classes += 1;
+        }
+        ByteClasses {
+            classes,
+            num_classes,
+        }
+    }
+}
+
+/// Incremental partition refinement over `0..alphabet_size`, used to build a
+/// [`ByteClasses`] from the `Byte`/`ByteSet` expressions reachable from some
+/// `ExprRef`.
+struct ByteClassBuilder {
+    classes: Vec<u8>,
+    num_classes: usize,
+}
+
+impl ByteClassBuilder {
+    fn new(alphabet_size: usize) -> Self {
+        ByteClassBuilder {
+            classes: vec![0u8; alphabet_size],
+            num_classes: if alphabet_size > 0 { 1 } else { 0 },
+        }
+    }
+
+    /// Split every existing class into the sub-class of bytes inside `set`
+    /// and the sub-class of bytes outside it.
+    fn refine(&mut self, set: &[u32]) {
+        if self.num_classes == 0 {
+            return;
+        }
+        let mut in_id: Vec<Option<u8>> = vec![None; self.num_classes];
+        let mut out_id: Vec<Option<u8>> = vec![None; self.num_classes];
+        let mut next_id: u8 = 0;
+        for b in 0..self.classes.len() {
+            let c = self.classes[b] as usize;
+            let slot = if byteset_contains(set, b) {
+                &mut in_id[c]
+            } else {
+                &mut out_id[c]
+            };
+            let id = match *slot {
+                Some(id) => id,
+                None => {
+                    let id = next_id;
+                    next_id += 1;
+                    *slot = Some(id);
+                    id
+                }
+            };
+            self.classes[b] = id;
+        }
+        self.num_classes = next_id as usize;
+    }
+
+    /// Renumber classes by the lowest byte in each one, so the returned ids
+    /// don't depend on the order `refine` was called in: the partition
+    /// itself is order-independent, only the temporary ids assigned while
+    /// building it are not.
+    fn finish(mut self) -> (Vec<u8>, usize) {
+        let mut remap: Vec<Option<u8>> = vec![None; self.num_classes];
+        let mut next_id: u8 = 0;
+        for &c in &self.classes {
+            let slot = &mut remap[c as usize];
+            if slot.is_none() {
+                *slot = Some(next_id);
+                next_id += 1;
+            }
+        }
+        for c in self.classes.iter_mut() {
+            *c = remap[*c as usize].unwrap();
+        }
+        (self.classes, next_id as usize)
+    }
+}
+
+impl ExprSet {
+    /// Builds a [`ByteClasses`] partition for `e`: bytes in the same class
+    /// are indistinguishable to every `Byte`/`ByteSet` reachable from `e`.
+    pub fn byte_classes(&mut self, e: ExprRef) -> ByteClasses {
+        let alphabet_size = self.alphabet_size;
+        let mut builder = ByteClassBuilder::new(alphabet_size);
+        self.simple_map(e, |es, _mapped, r| match es.get(r) {
+            Expr::Byte(b) => {
+                let mut s = byteset_256();
+                byteset_set(&mut s, b as usize);
+                builder.refine(&s);
+            }
+            Expr::ByteSet(s) => builder.refine(s),
+            _ => {}
+        });
+        let (classes_low, num_classes_low) = builder.finish();
+        ByteClasses::from_low_alphabet(classes_low, num_classes_low, alphabet_size)
+    }
+}
+
+// Magic tag and format version for `ExprSet::serialize()`'s blob, so a
+// reader can reject garbage or a future-incompatible format up front instead
+// of tripping the unsafe transmute deep inside `ExprTag::from_u8`.
+const EXPR_SET_MAGIC: u32 = 0x6465_7276; // ASCII "derv", arbitrary but stable
+const EXPR_SET_FORMAT_VERSION: u32 = 1;
+
+// Upper bound on a deserialized `alphabet_size`: large enough for any
+// alphabet this crate actually builds (byte alphabets, or Unicode
+// codepoint-range alphabets), small enough that `ExprSet::new`'s
+// `(alphabet_size + 31) / 32` can't overflow and its `vec![0xffffffff;
+// alphabet_words]` can't be used as an unbounded-allocation DoS.
+const MAX_ALPHABET_SIZE: usize = 1 << 24;
+
+/// Error returned by [`ExprSet::deserialize`] when a blob is truncated,
+/// mis-tagged, or otherwise cannot be trusted to decode into valid `Expr`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    AlphabetTooLarge(usize),
+    InvalidTag(u8),
+    InvalidRef { got: u32, max: u32 },
+    SentinelMismatch(&'static str),
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeserializeError::BadMagic => write!(f, "not an ExprSet blob (bad magic)"),
+            DeserializeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported ExprSet blob format version {v}")
+            }
+            DeserializeError::Truncated => write!(f, "truncated ExprSet blob"),
+            DeserializeError::AlphabetTooLarge(n) => {
+                write!(f, "alphabet_size {n} exceeds the maximum of {MAX_ALPHABET_SIZE}")
+            }
+            DeserializeError::InvalidTag(t) => write!(f, "invalid expr tag byte {t}"),
+            DeserializeError::InvalidRef { got, max } => {
+                write!(f, "expr ref {got} out of bounds (max {max})")
+            }
+            DeserializeError::SentinelMismatch(name) => {
+                write!(f, "reserved sentinel {name} did not round-trip")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+fn sentinel_name(id: u32) -> &'static str {
+    match id {
+        1 => "EMPTY_STRING",
+        2 => "NO_MATCH",
+        3 => "ANY_BYTE",
+        4 => "ANY_BYTE_STRING",
+        5 => "NON_EMPTY_BYTE_STRING",
+        _ => "?",
+    }
+}
+
+// Checks that `words` decodes to a legal `ExprTag` with in-bounds `ExprRef`
+// args (every ref must point at an already-inserted, earlier id) before we
+// ever hand it to `Expr::from_slice`, which trusts its input.
+fn validate_entry(id: u32, words: &[u32], alphabet_words: usize) -> Result<(), DeserializeError> {
+    let Some(&head) = words.first() else {
+        return Err(DeserializeError::Truncated);
+    };
+    let tag_byte = (head & 0xff) as u8;
+    if tag_byte == 0 || tag_byte > ExprTag::MAX_VAL {
+        return Err(DeserializeError::InvalidTag(tag_byte));
+    }
+    let check_ref = |r: u32| -> Result<(), DeserializeError> {
+        if r == 0 || r >= id {
+            Err(DeserializeError::InvalidRef {
+                got: r,
+                max: id.saturating_sub(1),
+            })
+        } else {
+            Ok(())
+        }
+    };
+    match ExprTag::from_u8(tag_byte) {
+        ExprTag::EmptyString | ExprTag::NoMatch => {
+            (words.len() == 1).then_some(()).ok_or(DeserializeError::Truncated)
+        }
+        ExprTag::Byte => (words.len() == 2)
+            .then_some(())
+            .ok_or(DeserializeError::Truncated),
+        ExprTag::ByteSet => (words.len() == 1 + alphabet_words)
+            .then_some(())
+            .ok_or(DeserializeError::Truncated),
+        ExprTag::RemainderIs => (words.len() == 3)
+            .then_some(())
+            .ok_or(DeserializeError::Truncated),
+        ExprTag::Lookahead => {
+            if words.len() != 3 {
+                return Err(DeserializeError::Truncated);
+            }
+            check_ref(words[1])
+        }
+        ExprTag::Not => {
+            if words.len() != 2 {
+                return Err(DeserializeError::Truncated);
+            }
+            check_ref(words[1])
+        }
+        ExprTag::Repeat => {
+            if words.len() != 4 {
+                return Err(DeserializeError::Truncated);
+            }
+            check_ref(words[1])
+        }
+        ExprTag::Concat | ExprTag::Or | ExprTag::And => {
+            words[1..].iter().try_for_each(|&a| check_ref(a))
+        }
+    }
+}
+
+impl ExprSet {
+    /// Serializes this `ExprSet` into a flat, versioned, self-describing
+    /// blob: a header (magic, format version, alphabet info, `digits`,
+    /// `cost`) followed by every hash-consed entry as a length-prefixed
+    /// `u32` word buffer, so a compiled set of regexes can be written to
+    /// disk once and loaded many times with [`ExprSet::deserialize`]
+    /// instead of being rebuilt from scratch in every process.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&EXPR_SET_MAGIC.to_le_bytes());
+        out.extend_from_slice(&EXPR_SET_FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.alphabet_size as u64).to_le_bytes());
+        out.extend_from_slice(&(self.alphabet_words as u64).to_le_bytes());
+        out.extend_from_slice(&self.digits);
+        out.extend_from_slice(&self.cost.to_le_bytes());
+        out.extend_from_slice(&(self.exprs.len() as u64).to_le_bytes());
+        for id in 1..self.exprs.len() as u32 {
+            let words = self.exprs.get(id);
+            out.extend_from_slice(&(words.len() as u32).to_le_bytes());
+            for w in words {
+                out.extend_from_slice(&w.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Inverse of [`ExprSet::serialize`]. Re-validates the reserved prefix
+    /// (`ExprRef(0)` is the empty sentinel, and `EMPTY_STRING`/`NO_MATCH`/
+    /// `ANY_BYTE`/`ANY_BYTE_STRING`/`NON_EMPTY_BYTE_STRING` occupy refs
+    /// `1..=5`) against what `ExprSet::new` reconstructs, and validates
+    /// every other entry via [`validate_entry`] before replaying it through
+    /// `start_insert`/`push_slice`/`finish_insert` (the same path `mk`
+    /// uses), so a corrupt blob is rejected instead of reaching the unsafe
+    /// transmute in `ExprTag::from_u8`.
+    pub fn deserialize(bytes: &[u8]) -> Result<ExprSet, DeserializeError> {
+        let mut pos = 0usize;
+        let mut take = |n: usize| -> Result<&[u8], DeserializeError> {
+            if pos + n > bytes.len() {
+                return Err(DeserializeError::Truncated);
+            }
+            let s = &bytes[pos..pos + n];
+            pos += n;
+            Ok(s)
+        };
+
+        let magic = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        if magic != EXPR_SET_MAGIC {
+            return Err(DeserializeError::BadMagic);
+        }
+        let version = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        if version != EXPR_SET_FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+        let alphabet_size = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+        if alphabet_size > MAX_ALPHABET_SIZE {
+            return Err(DeserializeError::AlphabetTooLarge(alphabet_size));
+        }
+        let alphabet_words = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+        let mut digits = [0u8; 10];
+        digits.copy_from_slice(take(10)?);
+        let cost = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let num_entries = u64::from_le_bytes(take(8)?.try_into().unwrap()) as u32;
+        // Entries 1..=5 are the reserved sentinels re-validated below; a
+        // blob that doesn't even claim to carry them can't be trusted.
+        if num_entries < 6 {
+            return Err(DeserializeError::Truncated);
+        }
+
+        let mut set = ExprSet::new(alphabet_size);
+        if set.alphabet_words != alphabet_words {
+            return Err(DeserializeError::Truncated);
+        }
+        set.digits = digits;
+        set.cost = cost;
+
+        for id in 1..num_entries {
+            let word_count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+            let raw = take(word_count * 4)?;
+            let words: Vec<u32> = raw
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect();
+            validate_entry(id, &words, set.alphabet_words)?;
+
+            if id <= 5 {
+                if set.exprs.get(id) != words.as_slice() {
+                    return Err(DeserializeError::SentinelMismatch(sentinel_name(id)));
+                }
+            } else {
+                set.exprs.start_insert();
+                set.exprs.push_slice(&words);
+                let new_id = set.exprs.finish_insert();
+                if new_id != id {
+                    return Err(DeserializeError::InvalidRef {
+                        got: new_id,
+                        max: id,
+                    });
+                }
+            }
+        }
+
+        Ok(set)
+    }
+}
+
+// Rough byte-frequency ranking, used to pick the rarest byte among several
+// that are all mandatory: lower scores are rarer in typical text/binary
+// input, so `required_byte` picks the candidate with the lowest score,
+// maximizing how far a `memchr` scan can skip ahead.
+fn byte_rarity_table() -> &'static [u16; 256] {
+    static TABLE: OnceLock<[u16; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut t = [200u16; 256];
+        for b in b'a'..=b'z' {
+            t[b as usize] = 950;
+        }
+        for b in b'A'..=b'Z' {
+            t[b as usize] = 850;
+        }
+        for b in b'0'..=b'9' {
+            t[b as usize] = 800;
+        }
+        t[b' ' as usize] = 1000;
+        t[b'\n' as usize] = 700;
+        t[b'\r' as usize] = 650;
+        t[b'\t' as usize] = 400;
+        for &p in b".,;:!?'\"-_()[]{}/\\=+*%<>@#&|^~".iter() {
+            t[p as usize] = 600;
+        }
+        for b in 0u16..0x20 {
+            // control bytes other than the whitespace set above: rarer than
+            // printable text, and ranked rarer than non-ASCII too.
+            if t[b as usize] == 200 {
+                t[b as usize] = 50;
+            }
+        }
+        for b in 0x80u16..=0xff {
+            t[b as usize] = 100;
+        }
+        t
+    })
+}
+
+impl ExprSet {
+    // Mandatory-byte set for `e`, as a 256-bit byteset: a bit is set iff
+    // that byte is forced to appear somewhere in every string `e` matches.
+    //
+    // Goes through the cached `simple_map` DAG traversal (like `byte_classes`
+    // does) rather than recursing directly, since hash-consing means the
+    // same sub-expression can be shared by exponentially many parents, and
+    // plain recursion would redo the work for each one.
+    fn required_bytes(&mut self, e: ExprRef) -> Vec<u32> {
+        self.simple_map(e, |es, mapped: &mut Vec<Vec<u32>>, r| match es.get(r) {
+            Expr::Byte(b) => {
+                let mut s = byteset_256();
+                byteset_set(&mut s, b as usize);
+                s
+            }
+            // Only a singleton byte set forces a specific byte: `[ab]` can
+            // be matched by a string containing only `'b'` and never `'a'`,
+            // so anything with more than one member forces nothing.
+            Expr::ByteSet(s) if s.iter().map(|w| w.count_ones()).sum::<u32>() == 1 => {
+                let mut set = byteset_256();
+                set[..s.len()].copy_from_slice(s);
+                set
+            }
+            Expr::Concat(_, _) | Expr::And(_, _) => {
+                let mut acc = byteset_256();
+                for child in mapped.iter() {
+                    byteset_union(&mut acc, child);
+                }
+                acc
+            }
+            Expr::Or(_, _) => {
+                let mut children = mapped.iter();
+                let mut acc = children.next().cloned().unwrap_or_else(byteset_256);
+                for child in children {
+                    byteset_intersection(&mut acc, child);
+                }
+                acc
+            }
+            Expr::Repeat(_, _, min, _) if min >= 1 => mapped[0].clone(),
+            // EmptyString, NoMatch, RemainderIs, Lookahead, Not, Repeat{0,_},
+            // and any other nullable subexpression force nothing.
+            _ => byteset_256(),
+        })
+    }
+
+    /// Returns a single byte that must appear *somewhere* in every string
+    /// matched by `e`, chosen to be the rarest such byte so a `memchr` scan
+    /// can skip as far as possible before the derivative engine needs to
+    /// run. Returns `None` when no byte is provably mandatory (e.g. `e` is
+    /// nullable, or is [`ExprRef::ANY_BYTE`] itself).
+    pub fn required_byte(&mut self, e: ExprRef) -> Option<u8> {
+        let set = self.required_bytes(e);
+        let rarity = byte_rarity_table();
+        (0..=255u8)
+            .filter(|&b| byteset_contains(&set, b as usize))
+            .min_by_key(|&b| rarity[b as usize])
+    }
+}
+
+/// A small prefilter that, given an `ExprRef`, can skip a haystack ahead to
+/// the next occurrence of a byte that is mandatory for every match, before
+/// handing control back to the derivative engine.
+pub struct RequiredBytePrefilter {
+    byte: u8,
+}
+
+impl RequiredBytePrefilter {
+    /// Builds a prefilter for `e`, if [`ExprSet::required_byte`] can find a
+    /// mandatory byte.
+    pub fn new(exprs: &mut ExprSet, e: ExprRef) -> Option<Self> {
+        exprs.required_byte(e).map(|byte| RequiredBytePrefilter { byte })
+    }
+
+    pub fn required_byte(&self) -> u8 {
+        self.byte
+    }
+
+    /// Index of the next occurrence of the required byte in `haystack` at
+    /// or after `from`, if any.
+    pub fn find_in(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        memchr::memchr(self.byte, &haystack[from..]).map(|i| i + from)
+    }
+}
+
+/// Error returned by [`ExprSet::parse_expr`] when the input isn't valid
+/// `expr_to_string` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedEnd,
+    UnexpectedChar(char, usize),
+    UnknownKeyword(String, usize),
+    EmptyList(&'static str),
+    InvalidNumber(usize),
+    ByteOutOfAlphabet(u32, usize),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseError::UnexpectedChar(c, pos) => {
+                write!(f, "unexpected character {c:?} at position {pos}")
+            }
+            ParseError::UnknownKeyword(kw, pos) => {
+                write!(f, "unknown keyword {kw:?} at position {pos}")
+            }
+            ParseError::EmptyList(what) => write!(f, "{what} requires at least one argument"),
+            ParseError::InvalidNumber(pos) => write!(f, "invalid number at position {pos}"),
+            ParseError::ByteOutOfAlphabet(b, pos) => {
+                write!(f, "byte {b} at position {pos} is outside the alphabet")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Cursor over the textual form emitted by `PrettyPrinter`/`expr_to_string`.
+struct ExprParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(s: &'a str) -> Self {
+        ExprParser {
+            s: s.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b) if b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(ParseError::UnexpectedChar(
+                self.peek().map(|b| b as char).unwrap_or('\0'),
+                self.pos,
+            ))
+        }
+    }
+
+    fn try_consume(&mut self, c: u8) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_ident(&mut self) -> &'a str {
+        self.skip_ws();
+        let start = self.pos;
+        while matches!(self.peek(), Some(b) if b.is_ascii_alphanumeric() || b == b'_') {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos]).unwrap()
+    }
+
+    fn parse_u32(&mut self) -> Result<u32, ParseError> {
+        self.skip_ws();
+        let start = self.pos;
+        while matches!(self.peek(), Some(b) if b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(ParseError::InvalidNumber(start));
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .unwrap()
+            .parse()
+            .map_err(|_| ParseError::InvalidNumber(start))
+    }
+
+    // One literal byte inside a `'...'` or `[...]` token, unescaping a
+    // handful of common escapes plus `\xNN`.
+    fn parse_escaped_byte(&mut self) -> Result<u8, ParseError> {
+        match self.peek() {
+            Some(b'\\') => {
+                self.pos += 1;
+                let b = match self.peek() {
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'r') => b'\r',
+                    Some(b'\\') => b'\\',
+                    Some(b'\'') => b'\'',
+                    Some(b']') => b']',
+                    Some(b'-') => b'-',
+                    Some(b'x') => {
+                        self.pos += 1;
+                        let hex = self
+                            .s
+                            .get(self.pos..self.pos + 2)
+                            .ok_or(ParseError::UnexpectedEnd)?;
+                        let hex = std::str::from_utf8(hex).map_err(|_| ParseError::UnexpectedEnd)?;
+                        let v = u8::from_str_radix(hex, 16).map_err(|_| ParseError::UnexpectedEnd)?;
+                        self.pos += 2;
+                        return Ok(v);
+                    }
+                    _ => return Err(ParseError::UnexpectedEnd),
+                };
+                self.pos += 1;
+                Ok(b)
+            }
+            Some(c) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
+
+impl ExprSet {
+    /// Parses the textual form produced by [`ExprSet::expr_to_string`] back
+    /// into an `ExprRef`, so `parse_expr(&expr_to_string(e)) == e` holds for
+    /// hash-consed sets. Every node is reconstructed via the usual `mk`/
+    /// `mk_repeat` builders rather than trusting any printed flags:
+    /// `NULLABLE`/`POSITIVE` are derived by `mk` for leaves, and computed
+    /// from the parsed children (here, or by `mk_repeat`) for every other
+    /// node.
+    pub fn parse_expr(&mut self, s: &str) -> Result<ExprRef, ParseError> {
+        let mut p = ExprParser::new(s);
+        let r = self.parse_expr_inner(&mut p)?;
+        p.skip_ws();
+        if p.pos != p.s.len() {
+            return Err(ParseError::UnexpectedChar(
+                p.peek().map(|b| b as char).unwrap_or('\0'),
+                p.pos,
+            ));
+        }
+        Ok(r)
+    }
+
+    fn parse_expr_inner(&mut self, p: &mut ExprParser) -> Result<ExprRef, ParseError> {
+        p.skip_ws();
+        match p.peek() {
+            Some(b'\'') => self.parse_byte_literal(p),
+            Some(b'[') => self.parse_byteset_literal(p),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let ident = p.parse_ident();
+                match ident {
+                    "EmptyString" => Ok(ExprRef::EMPTY_STRING),
+                    "NoMatch" => Ok(ExprRef::NO_MATCH),
+                    "RemainderIs" => {
+                        p.expect(b'(')?;
+                        let d = p.parse_u32()?;
+                        p.expect(b',')?;
+                        let r = p.parse_u32()?;
+                        p.expect(b')')?;
+                        let e = self.mk(Expr::RemainderIs(d, r));
+                        self.pay();
+                        Ok(e)
+                    }
+                    "Lookahead" => {
+                        p.expect(b'(')?;
+                        let inner = self.parse_expr_inner(p)?;
+                        p.expect(b',')?;
+                        let n = p.parse_u32()?;
+                        p.expect(b')')?;
+                        // A lookahead assertion is zero-width: it never
+                        // consumes input, so it's always nullable.
+                        let e = self.mk(Expr::Lookahead(ExprFlags::POSITIVE_NULLABLE, inner, n));
+                        self.pay();
+                        Ok(e)
+                    }
+                    "Not" => {
+                        p.expect(b'(')?;
+                        let inner = self.parse_expr_inner(p)?;
+                        p.expect(b')')?;
+                        let flags =
+                            ExprFlags::from_nullable_positive(!self.is_nullable(inner), true);
+                        let e = self.mk(Expr::Not(flags, inner));
+                        self.pay();
+                        Ok(e)
+                    }
+                    "Repeat" => {
+                        p.expect(b'{')?;
+                        let min = p.parse_u32()?;
+                        p.expect(b',')?;
+                        p.skip_ws();
+                        let max = if p.peek() == Some(b'}') {
+                            u32::MAX
+                        } else {
+                            p.parse_u32()?
+                        };
+                        p.expect(b'}')?;
+                        p.expect(b'(')?;
+                        let inner = self.parse_expr_inner(p)?;
+                        p.expect(b')')?;
+                        let e = self.mk_repeat(inner, min, max);
+                        self.pay();
+                        Ok(e)
+                    }
+                    "Concat" => {
+                        let args = self.parse_expr_list(p, b'(', b')')?;
+                        if args.is_empty() {
+                            return Err(ParseError::EmptyList("Concat"));
+                        }
+                        // A sequence is nullable iff every element is, and
+                        // can only match something if every element can.
+                        let nullable = args.iter().all(|&a| self.is_nullable(a));
+                        let positive = args.iter().all(|&a| self.is_positive(a));
+                        let flags = ExprFlags::from_nullable_positive(nullable, positive);
+                        let e = self.mk(Expr::Concat(flags, &args));
+                        self.pay();
+                        Ok(e)
+                    }
+                    "Or" => {
+                        let args = self.parse_expr_list(p, b'(', b')')?;
+                        if args.is_empty() {
+                            return Err(ParseError::EmptyList("Or"));
+                        }
+                        // A union is nullable/positive as soon as any
+                        // alternative is.
+                        let nullable = args.iter().any(|&a| self.is_nullable(a));
+                        let positive = args.iter().any(|&a| self.is_positive(a));
+                        let flags = ExprFlags::from_nullable_positive(nullable, positive);
+                        let e = self.mk(Expr::Or(flags, &args));
+                        self.pay();
+                        Ok(e)
+                    }
+                    "And" => {
+                        let args = self.parse_expr_list(p, b'(', b')')?;
+                        if args.is_empty() {
+                            return Err(ParseError::EmptyList("And"));
+                        }
+                        // An intersection is nullable only if every operand
+                        // accepts the empty string.
+                        let nullable = args.iter().all(|&a| self.is_nullable(a));
+                        let positive = args.iter().all(|&a| self.is_positive(a));
+                        let flags = ExprFlags::from_nullable_positive(nullable, positive);
+                        let e = self.mk(Expr::And(flags, &args));
+                        self.pay();
+                        Ok(e)
+                    }
+                    _ => Err(ParseError::UnknownKeyword(ident.to_string(), p.pos)),
+                }
+            }
+            Some(c) => Err(ParseError::UnexpectedChar(c as char, p.pos)),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_expr_list(
+        &mut self,
+        p: &mut ExprParser,
+        open: u8,
+        close: u8,
+    ) -> Result<Vec<ExprRef>, ParseError> {
+        p.expect(open)?;
+        let mut out = Vec::new();
+        p.skip_ws();
+        if p.peek() != Some(close) {
+            loop {
+                out.push(self.parse_expr_inner(p)?);
+                if p.try_consume(b',') {
+                    continue;
+                }
+                break;
+            }
+        }
+        p.expect(close)?;
+        Ok(out)
+    }
+
+    fn parse_byte_literal(&mut self, p: &mut ExprParser) -> Result<ExprRef, ParseError> {
+        p.expect(b'\'')?;
+        let b = p.parse_escaped_byte()?;
+        p.expect(b'\'')?;
+        if b as usize >= self.alphabet_size {
+            return Err(ParseError::ByteOutOfAlphabet(b as u32, p.pos));
+        }
+        let e = self.mk(Expr::Byte(b));
+        self.pay();
+        Ok(e)
+    }
+
+    fn parse_byteset_literal(&mut self, p: &mut ExprParser) -> Result<ExprRef, ParseError> {
+        p.expect(b'[')?;
+        let mut s = vec![0u32; self.alphabet_words];
+        loop {
+            p.skip_ws();
+            if p.peek() == Some(b']') {
+                break;
+            }
+            let lo = p.parse_escaped_byte()?;
+            let hi = if p.peek() == Some(b'-') {
+                p.pos += 1;
+                p.parse_escaped_byte()?
+            } else {
+                lo
+            };
+            if lo as usize >= self.alphabet_size || hi as usize >= self.alphabet_size || lo > hi {
+                return Err(ParseError::ByteOutOfAlphabet(lo.max(hi) as u32, p.pos));
+            }
+            byteset_set_range(&mut s, lo..=hi);
+        }
+        p.expect(b']')?;
+        let e = self.mk(Expr::ByteSet(&s));
+        self.pay();
+        Ok(e)
+    }
+}
+
+#[cfg(test)]
+mod required_byte_tests {
+    use super::*;
+
+    #[test]
+    fn ignores_multi_member_byte_sets() {
+        let mut es = ExprSet::new(256);
+        let a = es.mk(Expr::Byte(b'a'));
+        let mut bc = byteset_256();
+        byteset_set(&mut bc, b'b' as usize);
+        byteset_set(&mut bc, b'c' as usize);
+        let bc_ref = es.mk(Expr::ByteSet(&bc));
+
+        // `[bc]` alone forces nothing: a match can consist entirely of 'b'
+        // and never contain 'c', or vice versa.
+        assert_eq!(es.required_byte(bc_ref), None);
+
+        // `Concat('a', [bc])` still forces 'a': the prefilter must never
+        // pick 'b' or 'c', since either one can be legally absent.
+        let concat = es.mk(Expr::Concat(ExprFlags::ZERO, &[a, bc_ref]));
+        assert_eq!(es.required_byte(concat), Some(b'a'));
+    }
+
+    #[test]
+    fn singleton_byte_set_is_required() {
+        let mut es = ExprSet::new(256);
+        let mut single = byteset_256();
+        byteset_set(&mut single, b'x' as usize);
+        let e = es.mk(Expr::ByteSet(&single));
+        assert_eq!(es.required_byte(e), Some(b'x'));
+    }
+}
+
+#[cfg(test)]
+mod byte_classes_tests {
+    use super::*;
+
+    #[test]
+    fn partitions_bytes_by_the_sets_that_distinguish_them() {
+        let mut es = ExprSet::new(4);
+        let mut ab = byteset_256();
+        byteset_set(&mut ab, 0);
+        byteset_set(&mut ab, 1);
+        let ab_ref = es.mk(Expr::ByteSet(&ab));
+        let c_ref = es.mk(Expr::Byte(2));
+        let e = es.mk(Expr::Or(ExprFlags::ZERO, &[ab_ref, c_ref]));
+
+        let classes = es.byte_classes(e);
+        // 0 and 1 are never distinguished: every ByteSet/Byte reachable from
+        // `e` treats them identically.
+        assert_eq!(classes.get(0), classes.get(1));
+        // 2 is split off by the `Byte(2)` leaf, and 3 is split off by being
+        // in neither leaf.
+        assert_ne!(classes.get(0), classes.get(2));
+        assert_ne!(classes.get(2), classes.get(3));
+        assert_ne!(classes.get(0), classes.get(3));
+
+        // Bytes outside the declared alphabet always form their own class,
+        // even though nothing in `e` ever distinguishes them from each
+        // other.
+        assert_eq!(classes.get(4), classes.get(5));
+        assert_eq!(classes.get(4), classes.get(255));
+        assert_ne!(classes.get(4), classes.get(0));
+
+        assert_eq!(classes.num_classes(), 4);
+        assert_eq!(classes.representative_bytes().count(), 4);
+    }
+
+    #[test]
+    fn full_alphabet_has_no_out_of_alphabet_class() {
+        let mut es = ExprSet::new(256);
+        let e = es.mk(Expr::Byte(b'a'));
+        let classes = es.byte_classes(e);
+        // `[a]` vs. everything else, and nothing left over to split out.
+        assert_eq!(classes.num_classes(), 2);
+        assert_ne!(classes.get(b'a'), classes.get(b'b'));
+        assert_eq!(classes.get(b'b'), classes.get(b'z'));
+    }
+}
+
+#[cfg(test)]
+mod parse_expr_flags_tests {
+    use super::*;
+
+    #[test]
+    fn or_recomputes_nullable_regardless_of_printed_flags() {
+        let mut es = ExprSet::new(256);
+        let e = es.parse_expr("Or(EmptyString, NoMatch)").unwrap();
+        assert!(es.is_nullable(e));
+        assert!(es.is_positive(e));
+    }
+
+    #[test]
+    fn concat_is_nullable_only_if_every_element_is() {
+        let mut es = ExprSet::new(256);
+        let nullable = es.parse_expr("Concat(EmptyString, EmptyString)").unwrap();
+        assert!(es.is_nullable(nullable));
+
+        let non_nullable = es.parse_expr("Concat(EmptyString, 'a')").unwrap();
+        assert!(!es.is_nullable(non_nullable));
+    }
+
+    #[test]
+    fn not_flips_nullability() {
+        let mut es = ExprSet::new(256);
+        let e = es.parse_expr("Not(EmptyString)").unwrap();
+        assert!(!es.is_nullable(e));
+    }
+
+    #[test]
+    fn parse_expr_round_trips_through_expr_to_string() {
+        let mut es = ExprSet::new(256);
+        let e = es.parse_expr("Or(EmptyString, NoMatch)").unwrap();
+        let printed = es.expr_to_string(e);
+        let reparsed = es.parse_expr(&printed).unwrap();
+        assert_eq!(reparsed, e);
+    }
+}